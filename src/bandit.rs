@@ -0,0 +1,1199 @@
+//! Multi-armed bandit policies for root-only decisions.
+//!
+//! A [`Bandit`] keeps reward statistics for a fixed set of arms and asks a
+//! [`Policy`] which arm to pull next. No game tree is involved: the caller
+//! evaluates the chosen arm however it likes (a rollout, a simulator run, ...)
+//! and reports the reward back.
+//!
+//! Rewards are expected to lie in `[0, 1]`. [`Ucb1`] and [`EpsilonGreedy`]
+//! work with any bounded reward. [`Ucb1Tuned`] caps the variance at `1/4`,
+//! which is only a bound for rewards in `[0, 1]`. [`KlUcb`], [`Thompson`] and
+//! [`BayesUcb`] rely on the Bernoulli interpretation and clamp their inputs to
+//! that range.
+
+use std::error;
+use std::fmt;
+
+use rand::Rng;
+use rand_distr::{Beta, Distribution};
+
+/// Reward statistics of a single arm.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ArmStats {
+    pulls: u32,
+    sum: f64,
+    sum_sq: f64,
+}
+
+impl ArmStats {
+    /// Creates statistics for an arm that has never been pulled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns how many times the arm has been pulled.
+    pub fn pulls(&self) -> u32 {
+        self.pulls
+    }
+
+    /// Returns the sum of all rewards received.
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Returns the average reward, or `0` if the arm has never been pulled.
+    pub fn mean(&self) -> f64 {
+        if self.pulls == 0 {
+            0.0
+        } else {
+            self.sum / f64::from(self.pulls)
+        }
+    }
+
+    /// Returns the (population) variance of the rewards, or `0` if the arm has
+    /// never been pulled.
+    pub fn variance(&self) -> f64 {
+        if self.pulls == 0 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        (self.sum_sq / f64::from(self.pulls) - mean * mean).max(0.0)
+    }
+
+    /// Records a reward.
+    pub fn update(&mut self, reward: f64) {
+        self.pulls += 1;
+        self.sum += reward;
+        self.sum_sq += reward * reward;
+    }
+}
+
+/// A rule scoring arms; the bandit pulls the arm with the highest score.
+///
+/// Scores are only requested for arms that have been pulled at least once.
+pub trait Policy {
+    /// Returns the priority of `arm`, where `total_pulls` is the number of pulls
+    /// over all arms.
+    fn score<R: Rng + ?Sized>(&self, arm: &ArmStats, total_pulls: u32, rng: &mut R) -> f64;
+
+    /// Optionally picks an arm directly, bypassing the scores. Called once per
+    /// selection after every arm has been pulled; the default never does.
+    ///
+    /// [`Bandit::try_select`] still scores every arm after a preselection so
+    /// that non-finite scores are reported regardless of this choice.
+    fn preselect<R: Rng + ?Sized>(&self, _arms: &[ArmStats], _rng: &mut R) -> Option<usize> {
+        None
+    }
+}
+
+/// The UCB1 formula `mean + c * sqrt(ln(total) / pulls)`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ucb1 {
+    /// The exploration constant.
+    pub c: f64,
+}
+
+impl Default for Ucb1 {
+    fn default() -> Self {
+        Ucb1 {
+            c: std::f64::consts::SQRT_2,
+        }
+    }
+}
+
+impl Policy for Ucb1 {
+    fn score<R: Rng + ?Sized>(&self, arm: &ArmStats, total_pulls: u32, _rng: &mut R) -> f64 {
+        let n = f64::from(arm.pulls);
+        arm.mean() + self.c * (f64::from(total_pulls).ln() / n).sqrt()
+    }
+}
+
+/// UCB1-Tuned, which replaces the exploration constant of UCB1 with an upper
+/// bound on the arm's reward variance.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Ucb1Tuned;
+
+impl Policy for Ucb1Tuned {
+    fn score<R: Rng + ?Sized>(&self, arm: &ArmStats, total_pulls: u32, _rng: &mut R) -> f64 {
+        let n = f64::from(arm.pulls);
+        let log_total = f64::from(total_pulls).ln();
+        let variance_bound = arm.variance() + (2.0 * log_total / n).sqrt();
+        arm.mean() + (log_total / n * variance_bound.min(0.25)).sqrt()
+    }
+}
+
+/// KL-UCB for Bernoulli rewards.
+///
+/// The score is the largest `q` such that
+/// `pulls * kl(mean, q) <= ln(total) + c * ln(ln(total))`, found by bisection.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct KlUcb {
+    /// The weight of the `ln(ln(total))` term. `0` works well in practice; the
+    /// regret bound is proven for `3`.
+    pub c: f64,
+}
+
+impl KlUcb {
+    const ITERATIONS: usize = 32;
+}
+
+impl Policy for KlUcb {
+    fn score<R: Rng + ?Sized>(&self, arm: &ArmStats, total_pulls: u32, _rng: &mut R) -> f64 {
+        let mean = arm.mean().clamp(0.0, 1.0);
+        let log_total = f64::from(total_pulls).ln();
+        let mut budget = log_total;
+        if log_total > 1.0 {
+            budget += self.c * log_total.ln();
+        }
+        let budget = budget / f64::from(arm.pulls);
+
+        let mut low = mean;
+        let mut high = 1.0;
+        for _ in 0..Self::ITERATIONS {
+            let mid = (low + high) / 2.0;
+            if bernoulli_kl(mean, mid) > budget {
+                high = mid;
+            } else {
+                low = mid;
+            }
+        }
+        low
+    }
+}
+
+/// Returns the Kullback-Leibler divergence between Bernoulli distributions with
+/// means `p` and `q`.
+fn bernoulli_kl(p: f64, q: f64) -> f64 {
+    const EPS: f64 = 1e-15;
+    let p = p.clamp(EPS, 1.0 - EPS);
+    let q = q.clamp(EPS, 1.0 - EPS);
+    p * (p / q).ln() + (1.0 - p) * ((1.0 - p) / (1.0 - q)).ln()
+}
+
+/// Thompson sampling with a `Beta(1, 1)` prior, treating rewards as fractional
+/// Bernoulli successes.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Thompson;
+
+impl Policy for Thompson {
+    fn score<R: Rng + ?Sized>(&self, arm: &ArmStats, _total_pulls: u32, rng: &mut R) -> f64 {
+        let (alpha, beta) = beta_posterior(arm);
+        match Beta::new(alpha, beta) {
+            Ok(beta) => beta.sample(rng),
+            Err(_) => arm.mean(),
+        }
+    }
+}
+
+/// Bayes-UCB with a `Beta(1, 1)` prior, treating rewards as fractional
+/// Bernoulli successes.
+///
+/// The score is the `1 - 1 / (total * ln(total)^c)` quantile of the arm's
+/// posterior, so it is an optimistic estimate that tightens as evidence
+/// accumulates. While that level is not positive, which happens for very few
+/// total pulls and a large `c`, the score is the arm's mean instead.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BayesUcb {
+    /// The exponent of the `ln(total)` term. `0` works well in practice; the
+    /// regret bound is proven for `5`.
+    pub c: f64,
+}
+
+impl BayesUcb {
+    const ITERATIONS: usize = 40;
+}
+
+impl Policy for BayesUcb {
+    fn score<R: Rng + ?Sized>(&self, arm: &ArmStats, total_pulls: u32, _rng: &mut R) -> f64 {
+        let total = f64::from(total_pulls);
+        let level = 1.0 - 1.0 / (total * total.ln().powf(self.c));
+        if level.is_nan() || level <= 0.0 {
+            return arm.mean();
+        }
+        let (alpha, beta) = beta_posterior(arm);
+
+        let mut low = 0.0;
+        let mut high = 1.0;
+        for _ in 0..Self::ITERATIONS {
+            let mid = (low + high) / 2.0;
+            if regularized_beta(mid, alpha, beta) < level {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+        high
+    }
+}
+
+/// Returns the parameters of the Beta posterior of `arm` under a `Beta(1, 1)`
+/// prior.
+fn beta_posterior(arm: &ArmStats) -> (f64, f64) {
+    let n = f64::from(arm.pulls);
+    let wins = arm.sum.clamp(0.0, n);
+    (1.0 + wins, 1.0 + n - wins)
+}
+
+/// Returns the regularized incomplete beta function `I_x(a, b)`, i.e. the
+/// cumulative distribution function of `Beta(a, b)` at `x`.
+fn regularized_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    let ln_front = ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln();
+    // The continued fraction converges quickly only on this side of the mean;
+    // use the symmetry `I_x(a, b) = 1 - I_{1-x}(b, a)` on the other.
+    if x < (a + 1.0) / (a + b + 2.0) {
+        ln_front.exp() * beta_continued_fraction(x, a, b) / a
+    } else {
+        1.0 - ln_front.exp() * beta_continued_fraction(1.0 - x, b, a) / b
+    }
+}
+
+/// Evaluates the continued fraction of the incomplete beta function with the
+/// modified Lentz method.
+fn beta_continued_fraction(x: f64, a: f64, b: f64) -> f64 {
+    const MAX_ITERATIONS: usize = 200;
+    const EPS: f64 = 1e-14;
+    const TINY: f64 = 1e-300;
+
+    fn nonzero(v: f64) -> f64 {
+        if v.abs() < TINY {
+            TINY
+        } else {
+            v
+        }
+    }
+
+    let mut c = 1.0;
+    let mut d = 1.0 / nonzero(1.0 - (a + b) * x / (a + 1.0));
+    let mut h = d;
+    for m in 1..=MAX_ITERATIONS {
+        let m = m as f64;
+        let even = m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m));
+        d = 1.0 / nonzero(1.0 + even * d);
+        c = nonzero(1.0 + even / c);
+        h *= d * c;
+
+        let odd = -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0));
+        d = 1.0 / nonzero(1.0 + odd * d);
+        c = nonzero(1.0 + odd / c);
+        let delta = d * c;
+        h *= delta;
+        if (delta - 1.0).abs() < EPS {
+            break;
+        }
+    }
+    h
+}
+
+/// Returns `ln(Gamma(x))` for `x >= 0.5` using the Lanczos approximation.
+fn ln_gamma(x: f64) -> f64 {
+    const G: f64 = 7.0;
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    let x = x - 1.0;
+    let mut sum = COEFFICIENTS[0];
+    for (i, coefficient) in COEFFICIENTS.iter().enumerate().skip(1) {
+        sum += coefficient / (x + i as f64);
+    }
+    let t = x + G + 0.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Epsilon-greedy: pulls a uniformly random arm with probability `epsilon`
+/// and the arm with the highest mean otherwise.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EpsilonGreedy {
+    /// The probability of pulling a random arm.
+    pub epsilon: f64,
+}
+
+impl Default for EpsilonGreedy {
+    fn default() -> Self {
+        EpsilonGreedy { epsilon: 0.1 }
+    }
+}
+
+impl Policy for EpsilonGreedy {
+    fn score<R: Rng + ?Sized>(&self, arm: &ArmStats, _total_pulls: u32, _rng: &mut R) -> f64 {
+        arm.mean()
+    }
+
+    fn preselect<R: Rng + ?Sized>(&self, arms: &[ArmStats], rng: &mut R) -> Option<usize> {
+        if rng.gen::<f64>() < self.epsilon {
+            Some(rng.gen_range(0..arms.len()))
+        } else {
+            None
+        }
+    }
+}
+
+/// An error reported by the checked operations of [`Bandit`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The arm index is not smaller than the number of arms.
+    ArmOutOfRange { arm: usize, n_arms: usize },
+    /// The reward is NaN or infinite.
+    NonFiniteReward { arm: usize, reward: f64 },
+    /// The policy scored the arm as NaN or infinite, typically because of a
+    /// non-finite policy constant.
+    NonFiniteScore { arm: usize, score: f64 },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::ArmOutOfRange { arm, n_arms } => {
+                write!(f, "arm {} is out of range for {} arms", arm, n_arms)
+            }
+            Error::NonFiniteReward { arm, reward } => {
+                write!(f, "arm {} received non-finite reward {}", arm, reward)
+            }
+            Error::NonFiniteScore { arm, score } => {
+                write!(f, "policy scored arm {} as non-finite {}", arm, score)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A multi-armed bandit over a fixed number of arms.
+#[derive(Clone, Debug)]
+pub struct Bandit<P> {
+    policy: P,
+    arms: Vec<ArmStats>,
+    total_pulls: u32,
+}
+
+impl<P: Policy> Bandit<P> {
+    /// Creates a bandit with `n_arms` unpulled arms.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n_arms` is `0`.
+    pub fn new(n_arms: usize, policy: P) -> Self {
+        assert!(n_arms > 0, "a bandit needs at least one arm");
+        Bandit {
+            policy,
+            arms: vec![ArmStats::new(); n_arms],
+            total_pulls: 0,
+        }
+    }
+
+    /// Returns the policy.
+    pub fn policy(&self) -> &P {
+        &self.policy
+    }
+
+    /// Returns the policy mutably, e.g. to adjust its parameters between runs.
+    pub fn policy_mut(&mut self) -> &mut P {
+        &mut self.policy
+    }
+
+    /// Returns the statistics of every arm, indexed by arm.
+    pub fn arms(&self) -> &[ArmStats] {
+        &self.arms
+    }
+
+    /// Returns the number of pulls over all arms.
+    pub fn total_pulls(&self) -> u32 {
+        self.total_pulls
+    }
+
+    /// Returns the arm to pull next.
+    ///
+    /// Arms that have never been pulled are chosen first, in index order. Ties
+    /// are broken in favour of the lowest index. A NaN score never wins; if
+    /// every score is NaN the first arm is returned. Use
+    /// [`try_select`](Bandit::try_select) to detect non-finite scores instead.
+    pub fn select<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        self.select_checked(rng, false).0
+    }
+
+    /// Like [`select`](Bandit::select), but fails if the policy scores any arm
+    /// as NaN or infinite, since such scores make the choice meaningless.
+    ///
+    /// Every arm is scored even when [`Policy::preselect`] picks the arm, so the
+    /// check does not depend on the RNG. While some arm has never been pulled,
+    /// that arm is returned and no arm is scored.
+    pub fn try_select<R: Rng + ?Sized>(&self, rng: &mut R) -> Result<usize, Error> {
+        match self.select_checked(rng, true) {
+            (_, Some((arm, score))) => Err(Error::NonFiniteScore { arm, score }),
+            (best, None) => Ok(best),
+        }
+    }
+
+    /// Returns the selected arm together with the first arm scored as
+    /// non-finite and its score. Unless `check` is set, a preselected arm is
+    /// returned without scoring.
+    fn select_checked<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        check: bool,
+    ) -> (usize, Option<(usize, f64)>) {
+        if let Some(arm) = self.arms.iter().position(|arm| arm.pulls == 0) {
+            return (arm, None);
+        }
+        let preselected = self.policy.preselect(&self.arms, rng);
+        if let (Some(arm), false) = (preselected, check) {
+            return (arm, None);
+        }
+        let mut best = 0;
+        let mut best_score = f64::NEG_INFINITY;
+        let mut non_finite = None;
+        for (i, arm) in self.arms.iter().enumerate() {
+            let score = self.policy.score(arm, self.total_pulls, rng);
+            if !score.is_finite() {
+                non_finite = non_finite.or(Some((i, score)));
+            }
+            if score > best_score {
+                best = i;
+                best_score = score;
+            }
+        }
+        (preselected.unwrap_or(best), non_finite)
+    }
+
+    /// Records `reward` for `arm`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arm` is out of range.
+    pub fn update(&mut self, arm: usize, reward: f64) {
+        self.arms[arm].update(reward);
+        self.total_pulls += 1;
+    }
+
+    /// Like [`update`](Bandit::update), but rejects out-of-range arms and
+    /// non-finite rewards instead of panicking or poisoning the statistics.
+    pub fn try_update(&mut self, arm: usize, reward: f64) -> Result<(), Error> {
+        if arm >= self.arms.len() {
+            return Err(Error::ArmOutOfRange {
+                arm,
+                n_arms: self.arms.len(),
+            });
+        }
+        if !reward.is_finite() {
+            return Err(Error::NonFiniteReward { arm, reward });
+        }
+        self.update(arm, reward);
+        Ok(())
+    }
+
+    /// Selects an arm, evaluates it with `evaluate` and records the reward.
+    /// Returns the pulled arm.
+    pub fn pull<R, F>(&mut self, rng: &mut R, evaluate: F) -> usize
+    where
+        R: Rng + ?Sized,
+        F: FnOnce(usize) -> f64,
+    {
+        let arm = self.select(rng);
+        let reward = evaluate(arm);
+        self.update(arm, reward);
+        arm
+    }
+
+    /// Like [`pull`](Bandit::pull), but selects with
+    /// [`try_select`](Bandit::try_select) and records with
+    /// [`try_update`](Bandit::try_update). On error no statistics change.
+    pub fn try_pull<R, F>(&mut self, rng: &mut R, evaluate: F) -> Result<usize, Error>
+    where
+        R: Rng + ?Sized,
+        F: FnOnce(usize) -> f64,
+    {
+        let arm = self.try_select(rng)?;
+        let reward = evaluate(arm);
+        self.try_update(arm, reward)?;
+        Ok(arm)
+    }
+
+    /// Returns the most pulled arm, which is the usual recommendation once the
+    /// budget is spent. Ties are broken in favour of the higher mean.
+    pub fn most_pulled(&self) -> usize {
+        let mut best = 0;
+        for (i, arm) in self.arms.iter().enumerate().skip(1) {
+            let current = &self.arms[best];
+            if arm.pulls > current.pulls
+                || (arm.pulls == current.pulls && arm.mean() > current.mean())
+            {
+                best = i;
+            }
+        }
+        best
+    }
+}
+
+/// Chooses among `arms` with sequential halving, spending about `budget` pulls
+/// through `evaluate`, and returns the chosen arm.
+///
+/// The budget is split into `ceil(log2(arms.len()))` rounds. Each round pulls
+/// every remaining arm equally often and then discards the worse half by mean
+/// reward. Unlike the anytime policies above, this minimizes simple regret, so
+/// it is the better choice when only the final recommendation matters.
+///
+/// `arms` may hold statistics from earlier pulls and receives the new rewards.
+/// Every remaining arm is pulled at least once per round, so the budget is
+/// exceeded when it is smaller than the number of arms times the number of
+/// rounds. A single arm needs no rounds: it is returned without spending any
+/// budget. Arms whose mean is NaN rank below all others.
+///
+/// # Panics
+///
+/// Panics if `arms` is empty.
+pub fn sequential_halving<F>(arms: &mut [ArmStats], budget: u32, evaluate: F) -> usize
+where
+    F: FnMut(usize) -> f64,
+{
+    assert!(
+        !arms.is_empty(),
+        "sequential halving needs at least one arm"
+    );
+    let candidates = (0..arms.len()).collect();
+    halve(arms, candidates, budget, evaluate, |arms, arm| {
+        arms[arm].mean()
+    })
+}
+
+/// Gumbel-top-k root selection, as used by Gumbel MuZero.
+///
+/// Given prior logits over the arms, [`run`](Gumbel::run) perturbs them with
+/// Gumbel noise, keeps the `k` best arms and runs sequential halving among
+/// them, ranking arms by `gumbel + logit + sigma(mean)` with
+/// `sigma(q) = (c_visit + max_pulls) * c_scale * q`. The returned arm is a
+/// policy improvement over the priors even with budgets of a few pulls, where
+/// UCB-style policies cannot even try every arm.
+///
+/// Rewards are expected to lie in `[0, 1]` so that `c_scale` is meaningful.
+/// A logit of negative infinity (or NaN) masks its arm: it is never pulled and
+/// never returned, unless every arm is masked, in which case arm `0` is
+/// returned without any pulls.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Gumbel {
+    /// The number of arms sampled for sequential halving. `0` behaves like `1`,
+    /// which returns the top perturbed arm without any pulls.
+    pub k: usize,
+    /// The visit offset of the value transformation.
+    pub c_visit: f64,
+    /// The scale of the value transformation.
+    pub c_scale: f64,
+}
+
+impl Default for Gumbel {
+    fn default() -> Self {
+        Gumbel {
+            k: 16,
+            c_visit: 50.0,
+            c_scale: 1.0,
+        }
+    }
+}
+
+impl Gumbel {
+    /// Chooses among `arms` given their prior `logits`, spending about `budget`
+    /// pulls through `evaluate`, and returns the chosen arm.
+    ///
+    /// `arms` is treated as in [`sequential_halving`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arms` is empty or `logits` has a different length.
+    pub fn run<R, F>(
+        &self,
+        logits: &[f64],
+        arms: &mut [ArmStats],
+        budget: u32,
+        rng: &mut R,
+        evaluate: F,
+    ) -> usize
+    where
+        R: Rng + ?Sized,
+        F: FnMut(usize) -> f64,
+    {
+        assert!(!arms.is_empty(), "gumbel selection needs at least one arm");
+        assert_eq!(logits.len(), arms.len(), "one logit is needed per arm");
+
+        let perturbed: Vec<f64> = logits
+            .iter()
+            .map(|logit| {
+                let u: f64 = rng.gen_range(f64::MIN_POSITIVE..1.0);
+                logit - (-u.ln()).ln()
+            })
+            .collect();
+        let mut candidates: Vec<usize> = (0..arms.len()).collect();
+        candidates.sort_by(|&a, &b| nan_last(perturbed[b]).total_cmp(&nan_last(perturbed[a])));
+        // Masked arms sort last; keep them out unless nothing else is left.
+        let unmasked = candidates
+            .iter()
+            .take_while(|&&arm| perturbed[arm] > f64::NEG_INFINITY)
+            .count();
+        candidates.truncate(self.k.min(unmasked).max(1));
+
+        halve(arms, candidates, budget, evaluate, |arms, arm| {
+            let max_pulls = arms.iter().map(ArmStats::pulls).max().unwrap_or(0);
+            let sigma = (self.c_visit + f64::from(max_pulls)) * self.c_scale;
+            perturbed[arm] + sigma * arms[arm].mean()
+        })
+    }
+}
+
+/// Runs sequential halving over `candidates`, ranking them by `score` after
+/// each round, and returns the best remaining candidate.
+fn halve<F, S>(
+    arms: &mut [ArmStats],
+    mut candidates: Vec<usize>,
+    budget: u32,
+    mut evaluate: F,
+    score: S,
+) -> usize
+where
+    F: FnMut(usize) -> f64,
+    S: Fn(&[ArmStats], usize) -> f64,
+{
+    let rounds = usize::BITS - (candidates.len() - 1).leading_zeros();
+    for _ in 0..rounds {
+        let pulls = (budget / (candidates.len() as u32 * rounds)).max(1);
+        for &arm in &candidates {
+            for _ in 0..pulls {
+                arms[arm].update(evaluate(arm));
+            }
+        }
+        let mut ranked: Vec<(usize, f64)> = candidates
+            .iter()
+            .map(|&arm| (arm, nan_last(score(arms, arm))))
+            .collect();
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        candidates = ranked.into_iter().map(|(arm, _)| arm).collect();
+        candidates.truncate(candidates.len().div_ceil(2));
+    }
+    candidates[0]
+}
+
+/// Maps NaN to negative infinity so that a poisoned score always ranks last.
+fn nan_last(score: f64) -> f64 {
+    if score.is_nan() {
+        f64::NEG_INFINITY
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-9,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    fn stats(rewards: &[f64]) -> ArmStats {
+        let mut arm = ArmStats::new();
+        for &reward in rewards {
+            arm.update(reward);
+        }
+        arm
+    }
+
+    /// Pulls Bernoulli arms with the given means and returns the most pulled.
+    fn converge<P: Policy>(policy: P) -> usize {
+        let means = [0.3, 0.5, 0.7, 0.45];
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut env = StdRng::seed_from_u64(2);
+        let mut bandit = Bandit::new(means.len(), policy);
+        for _ in 0..3000 {
+            bandit.pull(&mut rng, |arm| {
+                if env.gen::<f64>() < means[arm] {
+                    1.0
+                } else {
+                    0.0
+                }
+            });
+        }
+        assert_eq!(bandit.total_pulls(), 3000);
+        bandit.most_pulled()
+    }
+
+    #[test]
+    fn arm_stats() {
+        let empty = ArmStats::new();
+        assert_eq!(empty.pulls(), 0);
+        assert_close(empty.mean(), 0.0);
+        assert_close(empty.variance(), 0.0);
+
+        let arm = stats(&[1.0, 0.0, 1.0, 0.0]);
+        assert_eq!(arm.pulls(), 4);
+        assert_close(arm.sum(), 2.0);
+        assert_close(arm.mean(), 0.5);
+        assert_close(arm.variance(), 0.25);
+    }
+
+    #[test]
+    fn ucb1_score() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let arm = stats(&[1.0, 0.0]);
+        let score = Ucb1 { c: 2.0 }.score(&arm, 8, &mut rng);
+        assert_close(score, 0.5 + 2.0 * (8f64.ln() / 2.0).sqrt());
+    }
+
+    #[test]
+    fn ucb1_tuned_score() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // High variance: the bound is capped at 1/4.
+        let arm = stats(&[1.0, 0.0]);
+        let score = Ucb1Tuned.score(&arm, 8, &mut rng);
+        assert_close(score, 0.5 + (8f64.ln() / 2.0 * 0.25).sqrt());
+
+        // Zero variance: only the confidence term remains in the bound.
+        let arm = stats(&[0.5; 10_000]);
+        let log_total = 20_000f64.ln();
+        let bound = (2.0 * log_total / 10_000.0).sqrt();
+        let score = Ucb1Tuned.score(&arm, 20_000, &mut rng);
+        assert_close(score, 0.5 + (log_total / 10_000.0 * bound).sqrt());
+    }
+
+    #[test]
+    fn kl_ucb_score() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let arm = stats(&[1.0, 0.0, 1.0, 0.0]);
+        let score = KlUcb { c: 0.0 }.score(&arm, 10, &mut rng);
+        assert!(score > 0.5 && score < 1.0);
+        assert!((4.0 * bernoulli_kl(0.5, score) - 10f64.ln()).abs() < 1e-6);
+
+        let arm = stats(&[1.0; 4]);
+        assert!(KlUcb { c: 0.0 }.score(&arm, 10, &mut rng) > 1.0 - 1e-6);
+    }
+
+    #[test]
+    fn thompson_samples_posterior() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let arm = stats(&[1.0; 100]);
+        let samples = 1000;
+        let mut total = 0.0;
+        for _ in 0..samples {
+            let score = Thompson.score(&arm, 100, &mut rng);
+            assert!((0.0..=1.0).contains(&score));
+            total += score;
+        }
+        // The posterior is Beta(101, 1), whose mean is 101 / 102.
+        assert!((total / f64::from(samples) - 101.0 / 102.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn ln_gamma_known_values() {
+        assert_close(ln_gamma(1.0), 0.0);
+        assert_close(ln_gamma(2.0), 0.0);
+        assert_close(ln_gamma(5.0), 24f64.ln());
+        assert_close(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln());
+        let ln_factorial_100: f64 = (1..=100).map(|k| f64::from(k).ln()).sum();
+        assert_close(ln_gamma(101.0), ln_factorial_100);
+    }
+
+    #[test]
+    fn regularized_beta_closed_forms() {
+        for &x in &[0.0, 0.01, 0.2, 0.5, 0.7, 0.99, 1.0] {
+            // Beta(2, 1) has CDF x^2 and Beta(1, 2) has CDF 1 - (1 - x)^2.
+            assert_close(regularized_beta(x, 2.0, 1.0), x * x);
+            assert_close(regularized_beta(x, 1.0, 2.0), 1.0 - (1.0 - x) * (1.0 - x));
+            assert_close(regularized_beta(x, 1.0, 1.0), x);
+        }
+    }
+
+    #[test]
+    fn regularized_beta_large_parameters() {
+        assert_close(regularized_beta(0.5, 500.0, 500.0), 0.5);
+
+        // For integer `a` and `b`, `I_x(a, b)` is the probability of at least
+        // `a` successes in `a + b - 1` Bernoulli trials with probability `x`.
+        let (a, b, x) = (60, 40, 0.55_f64);
+        let n = a + b - 1;
+        let mut term = (1.0 - x).powi(n);
+        let mut tail = 0.0;
+        for k in 0..=n {
+            if k >= a {
+                tail += term;
+            }
+            term *= f64::from(n - k) / f64::from(k + 1) * x / (1.0 - x);
+        }
+        assert_close(regularized_beta(x, f64::from(a), f64::from(b)), tail);
+    }
+
+    #[test]
+    fn bayes_ucb_score() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // One win gives Beta(2, 1); with two total pulls the level is 1/2,
+        // whose quantile is sqrt(1/2).
+        let arm = stats(&[1.0]);
+        let score = BayesUcb { c: 0.0 }.score(&arm, 2, &mut rng);
+        assert_close(score, 0.5f64.sqrt());
+
+        // One loss gives Beta(1, 2), whose p-quantile is 1 - sqrt(1 - p).
+        let arm = stats(&[0.0]);
+        let score = BayesUcb { c: 0.0 }.score(&arm, 10, &mut rng);
+        assert_close(score, 1.0 - 0.1f64.sqrt());
+    }
+
+    #[test]
+    fn bayes_ucb_falls_back_to_mean_without_level() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut bandit = Bandit::new(2, BayesUcb { c: 5.0 });
+        bandit.update(0, 0.0);
+        bandit.update(1, 1.0);
+        assert_close(bandit.policy().score(&bandit.arms()[1], 2, &mut rng), 1.0);
+        assert_eq!(bandit.select(&mut rng), 1);
+    }
+
+    #[test]
+    fn select_pulls_unpulled_arms_first() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut bandit = Bandit::new(3, Ucb1::default());
+        assert_eq!(bandit.select(&mut rng), 0);
+        bandit.update(0, 1.0);
+        assert_eq!(bandit.select(&mut rng), 1);
+        bandit.update(2, 1.0);
+        assert_eq!(bandit.select(&mut rng), 1);
+    }
+
+    #[test]
+    fn select_breaks_ties_by_lowest_index() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut bandit = Bandit::new(3, Ucb1::default());
+        for arm in 0..3 {
+            bandit.update(arm, 0.5);
+        }
+        assert_eq!(bandit.select(&mut rng), 0);
+    }
+
+    #[test]
+    fn most_pulled() {
+        let mut bandit = Bandit::new(2, Ucb1::default());
+        bandit.update(0, 0.0);
+        bandit.update(0, 0.0);
+        bandit.update(1, 1.0);
+        assert_eq!(bandit.most_pulled(), 0);
+
+        // Equal pulls: the higher mean wins.
+        bandit.update(1, 1.0);
+        assert_eq!(bandit.most_pulled(), 1);
+    }
+
+    #[test]
+    fn try_select_reports_nan_score() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut bandit = Bandit::new(1, Ucb1 { c: f64::INFINITY });
+        bandit.update(0, 0.5);
+        // `ln(1) = 0`, so the exploration term is `inf * 0 = NaN`.
+        match bandit.try_select(&mut rng) {
+            Err(Error::NonFiniteScore { arm: 0, score }) => assert!(score.is_nan()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn try_select_reports_infinite_score() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut bandit = Bandit::new(2, Ucb1 { c: f64::INFINITY });
+        bandit.update(0, 0.0);
+        bandit.update(1, 1.0);
+        assert_eq!(
+            bandit.try_select(&mut rng),
+            Err(Error::NonFiniteScore {
+                arm: 0,
+                score: f64::INFINITY
+            })
+        );
+    }
+
+    #[test]
+    fn select_falls_back_to_first_arm_when_all_scores_are_nan() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut bandit = Bandit::new(2, Ucb1 { c: f64::NAN });
+        bandit.update(0, 0.0);
+        bandit.update(1, 1.0);
+        assert_eq!(bandit.select(&mut rng), 0);
+        assert!(bandit.try_select(&mut rng).is_err());
+    }
+
+    #[test]
+    fn try_update_rejects_bad_input() {
+        let mut bandit = Bandit::new(2, Ucb1::default());
+        assert_eq!(
+            bandit.try_update(2, 0.5),
+            Err(Error::ArmOutOfRange { arm: 2, n_arms: 2 })
+        );
+        match bandit.try_update(0, f64::NAN) {
+            Err(Error::NonFiniteReward { arm: 0, reward }) => assert!(reward.is_nan()),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(
+            bandit.try_update(1, f64::INFINITY),
+            Err(Error::NonFiniteReward {
+                arm: 1,
+                reward: f64::INFINITY
+            })
+        );
+        assert_eq!(bandit.total_pulls(), 0);
+        assert_eq!(bandit.try_update(1, 0.5), Ok(()));
+        assert_eq!(bandit.arms()[1].pulls(), 1);
+    }
+
+    #[test]
+    fn try_pull_rejects_non_finite_reward() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut bandit = Bandit::new(2, Ucb1::default());
+        assert!(matches!(
+            bandit.try_pull(&mut rng, |_| f64::NAN),
+            Err(Error::NonFiniteReward { arm: 0, .. })
+        ));
+        assert_eq!(bandit.arms()[0], ArmStats::new());
+        assert_eq!(bandit.try_pull(&mut rng, |_| 1.0), Ok(0));
+        assert_eq!(bandit.total_pulls(), 1);
+    }
+
+    #[test]
+    fn sequential_halving_spends_budget_exactly() {
+        let means = [0.1, 0.2, 0.3, 0.4, 0.5, 0.9, 0.6, 0.7];
+        let mut env = StdRng::seed_from_u64(3);
+        let mut arms = vec![ArmStats::new(); means.len()];
+        let mut spent = 0;
+        let best = sequential_halving(&mut arms, 240, |arm| {
+            spent += 1;
+            if env.gen::<f64>() < means[arm] {
+                1.0
+            } else {
+                0.0
+            }
+        });
+        assert_eq!(best, 5);
+        // Three rounds of 80 pulls: 8 arms x 10, 4 x 20 and 2 x 40.
+        assert_eq!(spent, 240);
+        assert_eq!(arms.iter().map(ArmStats::pulls).sum::<u32>(), 240);
+        assert_eq!(arms[5].pulls(), 70);
+    }
+
+    #[test]
+    fn sequential_halving_single_arm_spends_nothing() {
+        let mut arms = [ArmStats::new()];
+        let best = sequential_halving(&mut arms, 100, |_| panic!("no pulls expected"));
+        assert_eq!(best, 0);
+        assert_eq!(arms[0].pulls(), 0);
+    }
+
+    #[test]
+    fn sequential_halving_ranks_nan_last() {
+        let mut arms = vec![ArmStats::new(); 2];
+        let best = sequential_halving(&mut arms, 10, |arm| if arm == 0 { f64::NAN } else { 0.0 });
+        assert_eq!(best, 1);
+    }
+
+    /// Draws the perturbed logits `Gumbel::run` draws with the same RNG state.
+    fn perturb(logits: &[f64], rng: &mut StdRng) -> Vec<f64> {
+        logits
+            .iter()
+            .map(|logit| logit - (-rng.gen_range(f64::MIN_POSITIVE..1.0).ln()).ln())
+            .collect()
+    }
+
+    #[test]
+    fn gumbel_never_pulls_masked_arms() {
+        let logits = [0.0, f64::NEG_INFINITY, 0.0, f64::NAN];
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut arms = vec![ArmStats::new(); logits.len()];
+            let gumbel = Gumbel {
+                k: 4,
+                ..Gumbel::default()
+            };
+            let best = gumbel.run(&logits, &mut arms, 32, &mut rng, |arm| {
+                assert!(arm == 0 || arm == 2, "pulled masked arm {}", arm);
+                if arm == 0 {
+                    0.0
+                } else {
+                    1.0
+                }
+            });
+            assert_eq!(best, 2);
+            assert_eq!(arms[1].pulls(), 0);
+            assert_eq!(arms[3].pulls(), 0);
+        }
+    }
+
+    #[test]
+    fn gumbel_all_masked_returns_first_arm() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut arms = vec![ArmStats::new(); 2];
+        let logits = [f64::NEG_INFINITY; 2];
+        let best = Gumbel::default().run(&logits, &mut arms, 32, &mut rng, |_| {
+            panic!("no pulls expected")
+        });
+        assert_eq!(best, 0);
+    }
+
+    #[test]
+    fn gumbel_k_one_returns_top_perturbed_arm() {
+        let logits = [0.5, 1.0, 0.0, 2.0, 1.5];
+        for &k in &[0, 1] {
+            for seed in 0..20 {
+                let expected = {
+                    let perturbed = perturb(&logits, &mut StdRng::seed_from_u64(seed));
+                    (0..logits.len())
+                        .max_by(|&a, &b| perturbed[a].total_cmp(&perturbed[b]))
+                        .unwrap()
+                };
+                let mut rng = StdRng::seed_from_u64(seed);
+                let mut arms = vec![ArmStats::new(); logits.len()];
+                let gumbel = Gumbel {
+                    k,
+                    ..Gumbel::default()
+                };
+                let best = gumbel.run(&logits, &mut arms, 32, &mut rng, |_| {
+                    panic!("no pulls expected")
+                });
+                assert_eq!(best, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn gumbel_prefers_best_rewarded_arm_with_equal_logits() {
+        let rewards = [0.0, 1.0, 0.2, 0.1];
+        for seed in 0..20 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut arms = vec![ArmStats::new(); rewards.len()];
+            let gumbel = Gumbel {
+                k: 4,
+                ..Gumbel::default()
+            };
+            let best = gumbel.run(&[0.0; 4], &mut arms, 64, &mut rng, |arm| rewards[arm]);
+            assert_eq!(best, 1);
+        }
+    }
+
+    /// A bandit whose arms have been pulled once with rewards `0.2`, `0.9` and
+    /// `0.5`.
+    fn epsilon_greedy(epsilon: f64) -> Bandit<EpsilonGreedy> {
+        let mut bandit = Bandit::new(3, EpsilonGreedy { epsilon });
+        for (arm, &reward) in [0.2, 0.9, 0.5].iter().enumerate() {
+            bandit.update(arm, reward);
+        }
+        bandit
+    }
+
+    #[test]
+    fn epsilon_greedy_without_exploration_picks_best_mean() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let bandit = epsilon_greedy(0.0);
+        for _ in 0..100 {
+            assert_eq!(bandit.select(&mut rng), 1);
+        }
+    }
+
+    #[test]
+    fn epsilon_greedy_with_full_exploration_reaches_every_arm() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let bandit = epsilon_greedy(1.0);
+        let mut seen = [false; 3];
+        for _ in 0..100 {
+            seen[bandit.select(&mut rng)] = true;
+        }
+        assert_eq!(seen, [true; 3]);
+    }
+
+    #[test]
+    fn epsilon_greedy_epsilon_is_adjustable() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut bandit = epsilon_greedy(1.0);
+        assert!((0..100).any(|_| bandit.select(&mut rng) != 1));
+        bandit.policy_mut().epsilon = 0.0;
+        for _ in 0..100 {
+            assert_eq!(bandit.select(&mut rng), 1);
+        }
+    }
+
+    /// Scores arms by mean and counts calls to `preselect`, which never picks.
+    struct CountingPreselect {
+        calls: std::cell::Cell<u32>,
+    }
+
+    impl Policy for CountingPreselect {
+        fn score<R: Rng + ?Sized>(&self, arm: &ArmStats, _total_pulls: u32, _rng: &mut R) -> f64 {
+            arm.mean()
+        }
+
+        fn preselect<R: Rng + ?Sized>(&self, _arms: &[ArmStats], _rng: &mut R) -> Option<usize> {
+            self.calls.set(self.calls.get() + 1);
+            None
+        }
+    }
+
+    #[test]
+    fn preselect_waits_for_unpulled_arms() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let policy = CountingPreselect {
+            calls: std::cell::Cell::new(0),
+        };
+        let mut bandit = Bandit::new(3, policy);
+        bandit.update(0, 1.0);
+        bandit.update(1, 1.0);
+        assert_eq!(bandit.select(&mut rng), 2);
+        assert_eq!(bandit.try_select(&mut rng), Ok(2));
+        assert_eq!(bandit.policy().calls.get(), 0);
+
+        bandit.update(2, 0.0);
+        assert_eq!(bandit.select(&mut rng), 0);
+        assert_eq!(bandit.policy().calls.get(), 1);
+    }
+
+    #[test]
+    fn try_select_checks_scores_after_preselect() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut bandit = Bandit::new(2, EpsilonGreedy { epsilon: 1.0 });
+        bandit.update(0, f64::NAN);
+        bandit.update(1, 0.5);
+        for _ in 0..50 {
+            match bandit.try_select(&mut rng) {
+                Err(Error::NonFiniteScore { arm: 0, score }) => assert!(score.is_nan()),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        // Unchecked selection still explores both arms.
+        let mut seen = [false; 2];
+        for _ in 0..50 {
+            seen[bandit.select(&mut rng)] = true;
+        }
+        assert_eq!(seen, [true; 2]);
+    }
+
+    #[test]
+    fn policies_converge_on_best_arm() {
+        assert_eq!(converge(Ucb1::default()), 2);
+        assert_eq!(converge(Ucb1Tuned), 2);
+        assert_eq!(converge(KlUcb::default()), 2);
+        assert_eq!(converge(Thompson), 2);
+        assert_eq!(converge(BayesUcb::default()), 2);
+        assert_eq!(converge(EpsilonGreedy::default()), 2);
+    }
+}
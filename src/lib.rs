@@ -0,0 +1,4 @@
+//! A Rust implementation of Monte Carlo Tree Search.
+
+pub mod bandit;
+pub mod wire;
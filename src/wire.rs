@@ -0,0 +1,276 @@
+//! Compact binary encoding of root analysis.
+//!
+//! The format is meant for sending the state of a search to thin clients at
+//! high frequency, so it carries only action indices and their statistics. All
+//! integers and floats are little endian:
+//!
+//! | size | field |
+//! |------|-------|
+//! | 1 | format version ([`VERSION`]) |
+//! | 4 | total visits of the root (`u32`) |
+//! | 2 | number of actions (`u16`) |
+//! | 10 each | action index (`u16`), visits (`u32`), value (`f32`) |
+
+use std::cmp::Reverse;
+use std::error;
+use std::fmt;
+
+/// The format version written by [`encode`]. [`decode`] rejects any other
+/// version.
+pub const VERSION: u8 = 1;
+
+const HEADER_LEN: usize = 1 + 4 + 2;
+const ACTION_LEN: usize = 2 + 4 + 4;
+
+/// Statistics of one root action.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ActionStats {
+    /// The index of the action in the application's own action numbering.
+    pub index: u16,
+    /// The number of visits of the action.
+    pub visits: u32,
+    /// The value of the action.
+    pub value: f32,
+}
+
+/// A summary of the root of a search.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RootAnalysis {
+    /// The number of visits of the root.
+    pub total_visits: u32,
+    /// The statistics of the root actions.
+    pub actions: Vec<ActionStats>,
+}
+
+impl RootAnalysis {
+    /// Keeps only the `k` most visited actions, sorted by descending visits.
+    /// Actions with equal visits keep their relative order.
+    pub fn retain_top_k(&mut self, k: usize) {
+        self.actions.sort_by_key(|action| Reverse(action.visits));
+        self.actions.truncate(k);
+    }
+
+    /// Returns the number of bytes [`encode`] writes for this analysis.
+    pub fn encoded_len(&self) -> usize {
+        HEADER_LEN + ACTION_LEN * self.actions.len()
+    }
+}
+
+/// An error of [`encode`] or [`decode`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The analysis has more actions than fit in the count field.
+    TooManyActions(usize),
+    /// The data was written in a format version this crate cannot read.
+    UnsupportedVersion(u8),
+    /// The data ends before the analysis is complete.
+    Truncated,
+    /// The data continues after the analysis; holds the number of extra bytes.
+    TrailingBytes(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Error::TooManyActions(n) => write!(f, "too many actions to encode: {}", n),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported format version {}", v),
+            Error::Truncated => write!(f, "data is truncated"),
+            Error::TrailingBytes(n) => write!(f, "{} trailing bytes after analysis", n),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// Appends the encoding of `analysis` to `buf`.
+pub fn encode(analysis: &RootAnalysis, buf: &mut Vec<u8>) -> Result<(), Error> {
+    let n = analysis.actions.len();
+    if n > usize::from(u16::MAX) {
+        return Err(Error::TooManyActions(n));
+    }
+
+    buf.reserve(analysis.encoded_len());
+    buf.push(VERSION);
+    buf.extend_from_slice(&analysis.total_visits.to_le_bytes());
+    buf.extend_from_slice(&(n as u16).to_le_bytes());
+    for action in &analysis.actions {
+        buf.extend_from_slice(&action.index.to_le_bytes());
+        buf.extend_from_slice(&action.visits.to_le_bytes());
+        buf.extend_from_slice(&action.value.to_le_bytes());
+    }
+    Ok(())
+}
+
+/// Decodes an analysis that occupies the whole of `data`.
+pub fn decode(data: &[u8]) -> Result<RootAnalysis, Error> {
+    let mut reader = Reader(data);
+    let version = reader.take::<1>()?[0];
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let total_visits = u32::from_le_bytes(reader.take()?);
+    let n = u16::from_le_bytes(reader.take()?);
+
+    let mut actions = Vec::with_capacity(usize::from(n).min(reader.0.len() / ACTION_LEN));
+    for _ in 0..n {
+        actions.push(ActionStats {
+            index: u16::from_le_bytes(reader.take()?),
+            visits: u32::from_le_bytes(reader.take()?),
+            value: f32::from_le_bytes(reader.take()?),
+        });
+    }
+
+    if !reader.0.is_empty() {
+        return Err(Error::TrailingBytes(reader.0.len()));
+    }
+    Ok(RootAnalysis {
+        total_visits,
+        actions,
+    })
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        if self.0.len() < N {
+            return Err(Error::Truncated);
+        }
+        let (head, rest) = self.0.split_at(N);
+        self.0 = rest;
+        let mut bytes = [0; N];
+        bytes.copy_from_slice(head);
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(index: u16, visits: u32, value: f32) -> ActionStats {
+        ActionStats {
+            index,
+            visits,
+            value,
+        }
+    }
+
+    fn sample() -> RootAnalysis {
+        RootAnalysis {
+            total_visits: 100,
+            actions: vec![action(3, 10, 0.25), action(7, 60, 0.75), action(1, 30, 0.5)],
+        }
+    }
+
+    fn encoded(analysis: &RootAnalysis) -> Vec<u8> {
+        let mut buf = Vec::new();
+        encode(analysis, &mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn round_trip_empty() {
+        let analysis = RootAnalysis::default();
+        let buf = encoded(&analysis);
+        assert_eq!(buf.len(), analysis.encoded_len());
+        assert_eq!(buf.len(), HEADER_LEN);
+        assert_eq!(decode(&buf), Ok(analysis));
+    }
+
+    #[test]
+    fn round_trip_actions() {
+        let analysis = sample();
+        let buf = encoded(&analysis);
+        assert_eq!(buf.len(), analysis.encoded_len());
+        assert_eq!(buf.len(), HEADER_LEN + 3 * ACTION_LEN);
+        assert_eq!(decode(&buf), Ok(analysis));
+    }
+
+    #[test]
+    fn golden_bytes() {
+        #[rustfmt::skip]
+        let expected = [
+            1,                      // version
+            100, 0, 0, 0,           // total visits
+            3, 0,                   // number of actions
+            3, 0, 10, 0, 0, 0, 0x00, 0x00, 0x80, 0x3e, // 3: 10 visits, 0.25
+            7, 0, 60, 0, 0, 0, 0x00, 0x00, 0x40, 0x3f, // 7: 60 visits, 0.75
+            1, 0, 30, 0, 0, 0, 0x00, 0x00, 0x00, 0x3f, // 1: 30 visits, 0.5
+        ];
+        assert_eq!(encoded(&sample()), expected);
+        assert_eq!(decode(&expected), Ok(sample()));
+    }
+
+    #[test]
+    fn encode_appends() {
+        let mut buf = vec![0xff];
+        encode(&sample(), &mut buf).unwrap();
+        assert_eq!(buf[0], 0xff);
+        assert_eq!(decode(&buf[1..]), Ok(sample()));
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let mut buf = encoded(&sample());
+        buf[0] = VERSION + 1;
+        assert_eq!(decode(&buf), Err(Error::UnsupportedVersion(VERSION + 1)));
+    }
+
+    #[test]
+    fn truncated() {
+        assert_eq!(decode(&[]), Err(Error::Truncated));
+
+        let buf = encoded(&sample());
+        let cut = HEADER_LEN + ACTION_LEN + 3;
+        assert_eq!(decode(&buf[..cut]), Err(Error::Truncated));
+        assert_eq!(decode(&buf[..buf.len() - 1]), Err(Error::Truncated));
+    }
+
+    #[test]
+    fn trailing_bytes() {
+        let mut buf = encoded(&sample());
+        buf.extend_from_slice(&[0, 0]);
+        assert_eq!(decode(&buf), Err(Error::TrailingBytes(2)));
+    }
+
+    #[test]
+    fn too_many_actions() {
+        let n = usize::from(u16::MAX) + 1;
+        let analysis = RootAnalysis {
+            total_visits: 0,
+            actions: vec![action(0, 0, 0.0); n],
+        };
+        let mut buf = Vec::new();
+        assert_eq!(encode(&analysis, &mut buf), Err(Error::TooManyActions(n)));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn retain_top_k() {
+        let mut analysis = sample();
+        analysis.retain_top_k(2);
+        assert_eq!(
+            analysis.actions,
+            vec![action(7, 60, 0.75), action(1, 30, 0.5)]
+        );
+    }
+
+    #[test]
+    fn retain_top_k_is_stable() {
+        let mut analysis = RootAnalysis {
+            total_visits: 40,
+            actions: vec![
+                action(4, 10, 0.1),
+                action(2, 20, 0.2),
+                action(9, 10, 0.3),
+                action(5, 10, 0.4),
+            ],
+        };
+        analysis.retain_top_k(3);
+        assert_eq!(
+            analysis.actions,
+            vec![action(2, 20, 0.2), action(4, 10, 0.1), action(9, 10, 0.3)]
+        );
+    }
+}